@@ -1,26 +1,59 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     ffi::OsStr,
-    io::{self, Write},
+    fs::ReadDir,
+    io::{self, Read, Write},
     path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
 
 use crossterm::{
     cursor,
-    event::{self, Event, KeyCode, KeyEvent},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     execute, queue, style,
     terminal::{self, ClearType},
     Result,
 };
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use syntect::{easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet};
+
+/// Name of the bookmarks file under the XDG config dir.
+const BOOKMARKS_FILE: &str = "bookmarks";
+
+/// How many directory entries a single background chunk reads before
+/// handing control back to the render loop.
+const DIR_LOAD_CHUNK: usize = 32;
+
+/// How long to wait after the last filesystem event before re-reading the
+/// directory, so a burst of changes (e.g. an editor save) only triggers a
+/// single rescan.
+const FS_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// How long to wait for a keypress before looping back around to let the
+/// directory loader and filesystem watcher make progress.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 #[derive(PartialEq)]
 enum OpKind {
     Out,
+    Refresh,
+    Copy,
+    Move,
+    Rename,
+    Delete,
 }
 
+/// A completed action, kept around so `cursor_position` can land the
+/// selection sensibly and so `u` can reverse it.
+///
+/// `path` is the original location (the entry that was acted on); `dest` is
+/// where it ended up, for the operations that have one.
 struct Op {
     kind: OpKind,
     path: Option<PathBuf>,
+    dest: Option<PathBuf>,
 }
 
 impl Op {
@@ -28,27 +61,533 @@ impl Op {
         Op {
             kind,
             path: Some(path),
+            dest: None,
+        }
+    }
+
+    fn with_dest(kind: OpKind, path: PathBuf, dest: PathBuf) -> Op {
+        Op {
+            kind,
+            path: Some(path),
+            dest: Some(dest),
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum RegisterMode {
+    Yank,
+    Cut,
+}
+
+/// The yank/cut register: the entry last copied or cut with `yy`/`dd`,
+/// waiting to be dropped somewhere with `p`.
+#[derive(Clone)]
+struct Register {
+    mode: RegisterMode,
+    path: PathBuf,
+}
+
+/// How many lines of a previewed file are read before the preview pane
+/// stops. A previewed directory is listed in full.
+const PREVIEW_MAX_LINES: usize = 200;
+
+/// How many bytes of a previewed file are read, so a huge file can't stall
+/// the render loop.
+const PREVIEW_MAX_BYTES: u64 = 10 * 1024;
+
+/// What's shown in the right-hand preview pane for the highlighted entry.
+enum Preview {
+    None,
+    Dir(Vec<PathBuf>),
+    Binary,
+    File(Vec<String>),
+}
+
+fn list_dir_sorted(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+fn build_preview(path: Option<&Path>) -> Preview {
+    let path = match path {
+        Some(path) => path,
+        None => return Preview::None,
+    };
+
+    if path.is_dir() {
+        return list_dir_sorted(path).map(Preview::Dir).unwrap_or(Preview::None);
+    }
+
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Preview::None,
+    };
+
+    let mut buf = Vec::new();
+    if file.take(PREVIEW_MAX_BYTES).read_to_end(&mut buf).is_err() {
+        return Preview::None;
+    }
+
+    if buf.contains(&0) {
+        return Preview::Binary;
+    }
+
+    let lines = String::from_utf8_lossy(&buf)
+        .lines()
+        .take(PREVIEW_MAX_LINES)
+        .map(str::to_string)
+        .collect();
+    Preview::File(lines)
+}
+
+/// One piece of a rendered line: an optional foreground color (`None` means
+/// the terminal's default) and the text it applies to.
+type StyledSpan = (Option<style::Color>, String);
+/// A full terminal row, built out of consecutive styled spans.
+type Line = Vec<StyledSpan>;
+
+fn plain_line(s: impl Into<String>) -> Line {
+    vec![(None, s.into())]
+}
+
+fn truncate_pad_line(line: &Line, width: usize) -> Line {
+    let mut out = Vec::new();
+    let mut remaining = width;
+    for (color, text) in line {
+        if remaining == 0 {
+            break;
+        }
+        let taken: String = text.chars().take(remaining).collect();
+        remaining -= taken.chars().count();
+        out.push((*color, taken));
+    }
+    if remaining > 0 {
+        out.push((None, " ".repeat(remaining)));
+    }
+    out
+}
+
+fn combine_row(parent: &Line, current: &Line, preview: &Line) -> Line {
+    let mut row = Vec::with_capacity(parent.len() + current.len() + preview.len() + 2);
+    row.extend(parent.iter().cloned());
+    row.push((None, String::from(" │ ")));
+    row.extend(current.iter().cloned());
+    row.push((None, String::from(" │ ")));
+    row.extend(preview.iter().cloned());
+    row
+}
+
+/// Highlights `raw_lines` from `path` with `syntect`, falling back to plain
+/// (uncolored) lines when the extension doesn't match a known syntax or a
+/// line fails to highlight.
+fn highlight_preview(
+    syntax_set: &SyntaxSet,
+    theme: &syntect::highlighting::Theme,
+    path: &Path,
+    raw_lines: &[String],
+) -> Vec<Line> {
+    let syntax = match syntax_set.find_syntax_for_file(path) {
+        Ok(Some(syntax)) => syntax,
+        _ => return raw_lines.iter().map(|line| plain_line(line.clone())).collect(),
+    };
+
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    raw_lines
+        .iter()
+        .map(|line| {
+            let line_with_newline = format!("{line}\n");
+            match highlighter.highlight_line(&line_with_newline, syntax_set) {
+                Ok(ranges) => ranges
+                    .into_iter()
+                    .map(|(style, text)| {
+                        let fg = style.foreground;
+                        (
+                            Some(style::Color::Rgb {
+                                r: fg.r,
+                                g: fg.g,
+                                b: fg.b,
+                            }),
+                            text.trim_end_matches('\n').to_string(),
+                        )
+                    })
+                    .collect(),
+                Err(_) => plain_line(line.clone()),
+            }
+        })
+        .collect()
+}
+
+/// Progressive, non-blocking `read_dir`.
+///
+/// `Idle` holds the in-progress listing (a queue of entries read but not yet
+/// handed out by `poll`, the underlying iterator, and whether more entries
+/// remain) whenever no chunk
+/// is currently being fetched. While a chunk is being read on a background
+/// thread the loader sits in `Pending`, holding the receiving end of the
+/// channel the thread will report back on, so the render loop never blocks
+/// waiting on the filesystem.
+enum DirLoader {
+    Idle(Option<(VecDeque<PathBuf>, ReadDir, bool)>),
+    Pending(mpsc::Receiver<Result<(VecDeque<PathBuf>, ReadDir, bool)>>),
+}
+
+impl DirLoader {
+    fn start(dir: &Path) -> Result<DirLoader> {
+        let read_dir = std::fs::read_dir(dir)?;
+        Ok(DirLoader::Idle(Some((VecDeque::new(), read_dir, true))))
+    }
+
+    /// Advances the load by at most one chunk and returns any newly
+    /// discovered entries. Never blocks: if a chunk is already being read
+    /// in the background, or the listing is already complete, it returns
+    /// an empty `Vec` immediately.
+    fn poll(&mut self) -> Result<Vec<PathBuf>> {
+        match std::mem::replace(self, DirLoader::Idle(None)) {
+            DirLoader::Idle(None) => Ok(Vec::new()),
+            DirLoader::Idle(Some((queue, read_dir, remaining))) => {
+                if !remaining {
+                    *self = DirLoader::Idle(Some((queue, read_dir, remaining)));
+                    return Ok(Vec::new());
+                }
+
+                let (tx, rx) = mpsc::channel();
+                thread::spawn(move || {
+                    let _ = tx.send(read_chunk(queue, read_dir, remaining));
+                });
+                *self = DirLoader::Pending(rx);
+                Ok(Vec::new())
+            }
+            DirLoader::Pending(rx) => match rx.try_recv() {
+                Ok(Ok((mut queue, read_dir, remaining))) => {
+                    let entries = queue.drain(..).collect();
+                    *self = DirLoader::Idle(Some((queue, read_dir, remaining)));
+                    Ok(entries)
+                }
+                Ok(Err(err)) => Err(err),
+                Err(_) => {
+                    *self = DirLoader::Pending(rx);
+                    Ok(Vec::new())
+                }
+            },
+        }
+    }
+
+    fn is_loading(&self) -> bool {
+        match self {
+            DirLoader::Pending(_) => true,
+            DirLoader::Idle(Some((_, _, remaining))) => *remaining,
+            DirLoader::Idle(None) => false,
+        }
+    }
+}
+
+fn read_chunk(
+    mut queue: VecDeque<PathBuf>,
+    mut read_dir: ReadDir,
+    mut remaining: bool,
+) -> Result<(VecDeque<PathBuf>, ReadDir, bool)> {
+    for _ in 0..DIR_LOAD_CHUNK {
+        match read_dir.next() {
+            Some(entry) => queue.push_back(entry?.path()),
+            None => {
+                remaining = false;
+                break;
+            }
+        }
+    }
+    Ok((queue, read_dir, remaining))
+}
+
+fn to_io_err<E: std::error::Error + Send + Sync + 'static>(err: E) -> io::Error {
+    io::Error::other(err)
+}
+
+fn copy_recursive(src: &Path, dest: &Path) -> Result<()> {
+    if src.is_dir() {
+        std::fs::create_dir_all(dest)?;
+        for entry in std::fs::read_dir(src)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+    } else {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(src, dest)?;
+    }
+    Ok(())
+}
+
+/// Returns `dest` if nothing is there yet, otherwise the first `name (n)ext`
+/// variant that's free, so a paste can never silently clobber an existing
+/// entry of the same name.
+fn unique_dest(dest: PathBuf) -> PathBuf {
+    if !dest.exists() {
+        return dest;
+    }
+    let parent = dest.parent().unwrap_or_else(|| Path::new(""));
+    let stem = dest
+        .file_stem()
+        .and_then(OsStr::to_str)
+        .unwrap_or("")
+        .to_string();
+    let ext = dest
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(|ext| format!(".{ext}"))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| parent.join(format!("{stem} ({n}){ext}")))
+        .find(|candidate| !candidate.exists())
+        .expect("an unbounded suffix search always finds a free name")
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Moves `src` to `dest`, preferring a plain rename and falling back to
+/// copy-then-remove when the two paths live on different filesystems (the
+/// case `fs::rename` can't handle). The returned `OpKind` records which
+/// path was taken so `u` can undo it the right way.
+fn move_path(src: &Path, dest: &Path) -> Result<OpKind> {
+    match std::fs::rename(src, dest) {
+        Ok(()) => Ok(OpKind::Rename),
+        Err(_) => {
+            copy_recursive(src, dest)?;
+            remove_path(src)?;
+            Ok(OpKind::Move)
+        }
+    }
+}
+
+fn restore_from_trash(original: &Path) -> Result<()> {
+    let items = trash::os_limited::list().map_err(to_io_err)?;
+    let item = items
+        .into_iter()
+        .filter(|item| item.original_path() == original)
+        .max_by_key(|item| item.time_deleted)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "nothing in trash to restore"))?;
+    trash::os_limited::restore_all(vec![item]).map_err(to_io_err)
+}
+
+/// Watches a single directory for create/remove/rename events and funnels
+/// them into a channel the main loop can poll without blocking.
+struct DirWatcher {
+    _watcher: RecommendedWatcher,
+    rx: mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl DirWatcher {
+    fn watch(dir: &Path) -> Result<DirWatcher> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event| { let _ = tx.send(event); }).map_err(to_io_err)?;
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(to_io_err)?;
+        Ok(DirWatcher { _watcher: watcher, rx })
+    }
+}
+
+/// Single-character directory bookmarks, persisted under the XDG config dir
+/// so they survive across sessions.
+struct Bookmarks {
+    marks: HashMap<char, PathBuf>,
+}
+
+impl Bookmarks {
+    /// Loads the bookmarks file if one exists; a missing file just means no
+    /// bookmarks have been saved yet.
+    fn load() -> Result<Bookmarks> {
+        let dirs = xdg::BaseDirectories::with_prefix("nuice").map_err(to_io_err)?;
+        let marks = match dirs.find_config_file(BOOKMARKS_FILE) {
+            Some(path) => parse_bookmarks(&std::fs::read_to_string(path)?),
+            None => HashMap::new(),
+        };
+        Ok(Bookmarks { marks })
+    }
+
+    /// Writes the current bookmarks to disk, creating the config dir if
+    /// needed.
+    fn save(&self) -> Result<()> {
+        let dirs = xdg::BaseDirectories::with_prefix("nuice").map_err(to_io_err)?;
+        let path = dirs.place_config_file(BOOKMARKS_FILE).map_err(to_io_err)?;
+        let mut contents = String::new();
+        for (letter, dir) in &self.marks {
+            contents.push_str(&format!("{}={}\n", letter, dir.display()));
+        }
+        std::fs::write(path, contents)
+    }
+}
+
+fn parse_bookmarks(contents: &str) -> HashMap<char, PathBuf> {
+    contents
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter_map(|(letter, dir)| {
+            let letter = letter.chars().next()?;
+            Some((letter, PathBuf::from(dir)))
+        })
+        .collect()
+}
+
 struct State {
     cursor: i32,
     dir: PathBuf,
     paths: HashMap<PathBuf, i32>,
     prev_op: Option<Op>,
-    screen_lines: Vec<String>,
+    screen_lines: Vec<Line>,
+    loader: DirLoader,
+    loaded: Vec<PathBuf>,
+    watcher: DirWatcher,
+    fs_event_pending: Option<Instant>,
+    register: Option<Register>,
+    pending_key: Option<char>,
+    bookmark_mode: Option<char>,
+    bookmarks: Bookmarks,
+    search: Option<String>,
+    search_input_active: bool,
+    show_hidden: bool,
+    scroll: usize,
+    visible: Vec<PathBuf>,
+    status: Option<String>,
+    parent_listing: Vec<PathBuf>,
+    preview: Preview,
+    highlighted_preview: Option<(PathBuf, Vec<Line>)>,
+    syntax_set: SyntaxSet,
+    theme: syntect::highlighting::Theme,
 }
 
 impl State {
     fn new() -> Result<State> {
-        Ok(State {
+        let dir = std::env::current_dir()?;
+        let loader = DirLoader::start(&dir)?;
+        let watcher = DirWatcher::watch(&dir)?;
+        let parent_listing = dir
+            .parent()
+            .map(list_dir_sorted)
+            .transpose()?
+            .unwrap_or_default();
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme = ThemeSet::load_defaults()
+            .themes
+            .get("base16-ocean.dark")
+            .expect("syntect bundles base16-ocean.dark")
+            .clone();
+        let mut state = State {
             cursor: 0,
-            dir: std::env::current_dir()?,
+            dir,
             paths: HashMap::new(),
             prev_op: None,
-            screen_lines: format_screen_lines(0, get_dir_content()?)?,
-        })
+            screen_lines: Vec::new(),
+            loader,
+            loaded: Vec::new(),
+            watcher,
+            fs_event_pending: None,
+            register: None,
+            pending_key: None,
+            bookmark_mode: None,
+            bookmarks: Bookmarks::load()?,
+            search: None,
+            search_input_active: false,
+            show_hidden: false,
+            scroll: 0,
+            visible: Vec::new(),
+            status: None,
+            parent_listing,
+            preview: Preview::None,
+            highlighted_preview: None,
+            syntax_set,
+            theme,
+        };
+        state.refresh_visible();
+        state.screen_lines = format_screen_lines(&mut state)?;
+        Ok(state)
+    }
+
+    /// Recomputes `self.visible` from the current listing, search query and
+    /// `show_hidden` flag. Called once per frame rather than on every read,
+    /// since it's an O(n) pass over the (potentially large) loaded listing.
+    fn refresh_visible(&mut self) {
+        self.visible = compute_visible_entries(self);
+    }
+
+    /// Restarts directory enumeration from scratch, e.g. after the current
+    /// directory changes.
+    fn reload(&mut self) -> Result<()> {
+        self.loader = DirLoader::start(&self.dir)?;
+        self.loaded.clear();
+        Ok(())
+    }
+
+    /// Re-registers the filesystem watch against `self.dir`, used whenever
+    /// the current directory changes.
+    fn rewatch(&mut self) -> Result<()> {
+        self.watcher = DirWatcher::watch(&self.dir)?;
+        self.fs_event_pending = None;
+        Ok(())
+    }
+
+    /// Drains any pending filesystem events, marking a debounce deadline so
+    /// a burst of changes collapses into a single rescan.
+    fn drain_fs_events(&mut self) {
+        let mut changed = false;
+        while let Ok(event) = self.watcher.rx.try_recv() {
+            if let Ok(event) = event {
+                if matches!(
+                    event.kind,
+                    notify::EventKind::Create(_)
+                        | notify::EventKind::Remove(_)
+                        | notify::EventKind::Modify(notify::event::ModifyKind::Name(_))
+                ) {
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.fs_event_pending = Some(Instant::now());
+        }
+    }
+
+    /// Re-reads the current directory after a debounced filesystem change,
+    /// re-resolving the cursor against the new listing by filename so the
+    /// selection stays put when possible.
+    ///
+    /// Our own `delete_selected`/`paste` touch files inside the watched
+    /// directory, so this also fires (debounced) right after one of those
+    /// ops completes. Only overwrite `prev_op` with `Refresh` when it isn't
+    /// already holding a reversible op — otherwise the self-triggered
+    /// refresh would erase the undo record before the user had a chance to
+    /// press `u`.
+    fn refresh_after_fs_change(&mut self) -> Result<()> {
+        let selected = self.visible.get(self.cursor as usize).cloned();
+        self.fs_event_pending = None;
+        self.reload()?;
+        self.paths.remove(&self.dir);
+        let undoable = matches!(
+            self.prev_op,
+            Some(Op {
+                kind: OpKind::Copy | OpKind::Move | OpKind::Rename | OpKind::Delete,
+                ..
+            })
+        );
+        if !undoable {
+            self.prev_op = Some(Op::new(
+                OpKind::Refresh,
+                selected.unwrap_or_else(|| self.dir.clone()),
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -67,6 +606,7 @@ where
     terminal::enable_raw_mode()?;
 
     let mut state = State::new()?;
+    let keymap = default_keymap();
 
     loop {
         queue!(
@@ -79,22 +619,62 @@ where
 
         state.dir = std::env::current_dir()?;
 
+        let chunk = state.loader.poll()?;
+        if !chunk.is_empty() {
+            state.loaded.extend(chunk);
+            state.loaded.sort();
+        }
+
+        state.drain_fs_events();
+        if let Some(since) = state.fs_event_pending {
+            if since.elapsed() >= FS_DEBOUNCE {
+                state.refresh_after_fs_change()?;
+            }
+        }
+
+        state.refresh_visible();
         state.cursor = cursor_position(&state)?;
 
-        state.screen_lines = format_screen_lines(state.cursor, get_dir_content()?)?;
+        state.parent_listing = state
+            .dir
+            .parent()
+            .map(list_dir_sorted)
+            .transpose()?
+            .unwrap_or_default();
+        state.preview = build_preview(selected_path(&state).as_deref());
+
+        state.screen_lines = format_screen_lines(&mut state)?;
 
         for line in &state.screen_lines {
-            queue!(w, style::Print(line), cursor::MoveToNextLine(1))?;
+            for (color, text) in line {
+                match color {
+                    Some(color) => queue!(w, style::SetForegroundColor(*color), style::Print(text))?,
+                    None => queue!(w, style::ResetColor, style::Print(text))?,
+                }
+            }
+            queue!(w, style::ResetColor, cursor::MoveToNextLine(1))?;
+        }
+
+        if state.search_input_active {
+            let query = state.search.clone().unwrap_or_default();
+            queue!(w, cursor::MoveToNextLine(1), style::Print(format!("/{query}")))?;
+        } else if let Some(status) = &state.status {
+            queue!(w, cursor::MoveToNextLine(1), style::Print(status))?;
         }
 
         w.flush()?;
 
-        match read_char()? {
-            'q' => break,
-            char => handle_keypress(&char, &mut state)?,
+        match poll_key(INPUT_POLL_INTERVAL)? {
+            Some(event) if state.search_input_active => {
+                if let Some(key) = classify_search_key(event) {
+                    handle_search_key(key, &mut state);
+                }
+            }
+            Some(event) if handle_keypress(event, &mut state, &keymap)? => break,
+            Some(_) | None => {}
         };
 
-        state.paths.insert(state.dir, state.cursor);
+        state.paths.insert(state.dir.clone(), state.cursor);
     }
 
     execute!(
@@ -108,6 +688,7 @@ where
 }
 
 fn cursor_position(state: &State) -> Result<i32> {
+    let visible = &state.visible;
     let cursor = if state.paths.contains_key(&state.dir) {
         match state.paths.get(&state.dir) {
             Some(cursor) => *cursor,
@@ -115,84 +696,471 @@ fn cursor_position(state: &State) -> Result<i32> {
         }
     } else {
         match &state.prev_op {
-            Some(op) if op.kind == OpKind::Out => {
-                let last = match op.path.as_ref() {
-                    Some(path) => match path.file_name() {
-                        Some(v) => v,
-                        None => OsStr::new(""),
-                    },
-                    None => OsStr::new(""),
-                };
-                let index = get_dir_content()?
-                    .iter()
-                    .position(|x| x.file_name() == Some(last))
-                    .unwrap_or(0);
-                index as i32
+            Some(op) if op.kind == OpKind::Out || op.kind == OpKind::Refresh => {
+                let name = op.path.as_ref().and_then(|path| path.file_name());
+                find_by_filename(visible, name)
+            }
+            Some(op) if matches!(op.kind, OpKind::Copy | OpKind::Move | OpKind::Rename) => {
+                let name = op.dest.as_ref().and_then(|path| path.file_name());
+                find_by_filename(visible, name)
             }
+            // The deleted entry is gone, so there's nothing to find by name;
+            // staying at the same row lands on whatever shifted up into it.
+            Some(op) if op.kind == OpKind::Delete => state.cursor,
             Some(_) => 0,
             None => 0,
         }
     };
-    Ok(cursor)
+    // The listing may still be loading in the background, so clamp against
+    // what has actually been read so far rather than assuming it's complete.
+    let max = (visible.len() as i32 - 1).max(0);
+    Ok(cursor.clamp(0, max))
+}
+
+fn find_by_filename(loaded: &[PathBuf], name: Option<&OsStr>) -> i32 {
+    let name = name.unwrap_or_else(|| OsStr::new(""));
+    loaded
+        .iter()
+        .position(|x| x.file_name() == Some(name))
+        .unwrap_or(0) as i32
+}
+
+fn selected_path(state: &State) -> Option<PathBuf> {
+    state.visible.get(state.cursor as usize).cloned()
+}
+
+/// Drops the yanked/cut entry into the current directory. Yanked entries
+/// stay in the register so they can be pasted again; cut entries are
+/// consumed by the move.
+fn paste(state: &mut State) -> Result<()> {
+    let register = match state.register.clone() {
+        Some(register) => register,
+        None => {
+            state.status = Some("nothing to paste".to_string());
+            return Ok(());
+        }
+    };
+    let name = match register.path.file_name() {
+        Some(name) => name.to_os_string(),
+        None => {
+            state.status = Some("nothing to paste".to_string());
+            return Ok(());
+        }
+    };
+    let dest = unique_dest(state.dir.join(&name));
+    let op = match register.mode {
+        RegisterMode::Yank => {
+            copy_recursive(&register.path, &dest)?;
+            Op::with_dest(OpKind::Copy, register.path.clone(), dest.clone())
+        }
+        RegisterMode::Cut => {
+            let kind = move_path(&register.path, &dest)?;
+            state.register = None;
+            Op::with_dest(kind, register.path.clone(), dest.clone())
+        }
+    };
+    state.status = Some(format!("pasted {}", dest.display()));
+    state.prev_op = Some(op);
+    state.paths.remove(&state.dir);
+    state.reload()?;
+    Ok(())
+}
+
+fn delete_selected(state: &mut State) -> Result<()> {
+    let path = match selected_path(state) {
+        Some(path) => path,
+        None => {
+            state.status = Some("nothing to delete".to_string());
+            return Ok(());
+        }
+    };
+    trash::delete(&path).map_err(to_io_err)?;
+    state.status = Some(format!("deleted {}", path.display()));
+    state.prev_op = Some(Op::new(OpKind::Delete, path));
+    state.paths.remove(&state.dir);
+    state.reload()?;
+    Ok(())
 }
 
-fn handle_keypress<'a>(char: &char, state: &'a mut State) -> Result<&'a mut State> {
-    let state = match char {
-        'j' => {
+/// Reverses the last recorded operation, if it's the kind of thing that can
+/// be reversed.
+fn undo_last(state: &mut State) -> Result<String> {
+    let op = match state.prev_op.take() {
+        Some(op) => op,
+        None => return Ok("nothing to undo".to_string()),
+    };
+    let message = match op.kind {
+        OpKind::Copy => {
+            if let Some(dest) = &op.dest {
+                remove_path(dest)?;
+            }
+            "undid copy".to_string()
+        }
+        OpKind::Rename => {
+            if let (Some(src), Some(dest)) = (&op.path, &op.dest) {
+                std::fs::rename(dest, src)?;
+            }
+            "undid move".to_string()
+        }
+        OpKind::Move => {
+            if let (Some(src), Some(dest)) = (&op.path, &op.dest) {
+                copy_recursive(dest, src)?;
+                remove_path(dest)?;
+            }
+            "undid move".to_string()
+        }
+        OpKind::Delete => {
+            if let Some(path) = &op.path {
+                restore_from_trash(path)?;
+            }
+            "undid delete".to_string()
+        }
+        OpKind::Out | OpKind::Refresh => "nothing to undo".to_string(),
+    };
+    state.reload()?;
+    Ok(message)
+}
+
+/// A navigation or file-operation command, bound to keys through `Keymap`
+/// instead of being matched on directly, so non-char keys (arrows, Enter)
+/// can be bound and the bindings can eventually be loaded from config.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Action {
+    MoveDown,
+    MoveUp,
+    MoveOut,
+    MoveIn,
+    Top,
+    Bottom,
+    ToggleHidden,
+    Quit,
+    StartYank,
+    StartCut,
+    Paste,
+    Delete,
+    Undo,
+    StartBookmarkSet,
+    StartBookmarkJump,
+    StartSearch,
+}
+
+type Keymap = HashMap<KeyEvent, Action>;
+
+/// Builds the built-in keybindings. Keyed on `code`/`modifiers` only (via
+/// `KeyEvent::new`, which normalizes `kind`/`state`), matching the repo's
+/// existing habit of ignoring everything but those two fields.
+fn default_keymap() -> Keymap {
+    let bindings = [
+        (KeyCode::Char('j'), Action::MoveDown),
+        (KeyCode::Down, Action::MoveDown),
+        (KeyCode::Char('k'), Action::MoveUp),
+        (KeyCode::Up, Action::MoveUp),
+        (KeyCode::Char('h'), Action::MoveOut),
+        (KeyCode::Left, Action::MoveOut),
+        (KeyCode::Char('l'), Action::MoveIn),
+        (KeyCode::Right, Action::MoveIn),
+        (KeyCode::Enter, Action::MoveIn),
+        (KeyCode::Char('g'), Action::Top),
+        (KeyCode::Char('G'), Action::Bottom),
+        (KeyCode::Char('.'), Action::ToggleHidden),
+        (KeyCode::Char('q'), Action::Quit),
+        (KeyCode::Char('y'), Action::StartYank),
+        (KeyCode::Char('d'), Action::StartCut),
+        (KeyCode::Char('p'), Action::Paste),
+        (KeyCode::Char('x'), Action::Delete),
+        (KeyCode::Char('u'), Action::Undo),
+        (KeyCode::Char('m'), Action::StartBookmarkSet),
+        (KeyCode::Char('`'), Action::StartBookmarkJump),
+        (KeyCode::Char('/'), Action::StartSearch),
+    ];
+    bindings
+        .into_iter()
+        .map(|(code, action)| (KeyEvent::new(code, KeyModifiers::NONE), action))
+        .collect()
+}
+
+/// Handles one keypress, returning `true` if the caller should quit.
+///
+/// Bookmark letters (after `m`/`` ` ``) and the second half of a `yy`/`dd`
+/// combo are read straight off the key's `char`, since they're arguments to
+/// the action that started them rather than actions themselves; everything
+/// else goes through `keymap`.
+fn handle_keypress(event: KeyEvent, state: &mut State, keymap: &Keymap) -> Result<bool> {
+    if let Some(mode) = state.bookmark_mode.take() {
+        if let KeyCode::Char(char) = event.code {
+            match mode {
+                'm' => {
+                    state.bookmarks.marks.insert(char, state.dir.clone());
+                    state.bookmarks.save()?;
+                    state.status = Some(format!("bookmarked '{char}'"));
+                }
+                '`' => match state.bookmarks.marks.get(&char).cloned() {
+                    Some(dir) => {
+                        std::env::set_current_dir(&dir)?;
+                        state.dir = std::env::current_dir()?;
+                        state.search = None;
+                        state.reload()?;
+                        state.rewatch()?;
+                        state.status = Some(format!("jumped to '{char}'"));
+                    }
+                    None => {
+                        state.status = Some(format!("no bookmark '{char}'"));
+                    }
+                },
+                _ => {}
+            }
+        }
+        return Ok(false);
+    }
+
+    if let Some(pending) = state.pending_key.take() {
+        if let KeyCode::Char(char) = event.code {
+            match (pending, char) {
+                ('y', 'y') => {
+                    if let Some(path) = selected_path(state) {
+                        state.status = Some(format!("yanked {}", path.display()));
+                        state.register = Some(Register {
+                            mode: RegisterMode::Yank,
+                            path,
+                        });
+                    }
+                }
+                ('d', 'd') => {
+                    if let Some(path) = selected_path(state) {
+                        state.status = Some(format!("cut {}", path.display()));
+                        state.register = Some(Register {
+                            mode: RegisterMode::Cut,
+                            path,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        return Ok(false);
+    }
+
+    let action = match keymap.get(&KeyEvent::new(event.code, event.modifiers)) {
+        Some(action) => *action,
+        None => return Ok(false),
+    };
+
+    match action {
+        Action::Quit => return Ok(true),
+        Action::MoveDown => {
             state.cursor = move_down(state)?;
             state.prev_op = None;
-            state
+            state.status = None;
         }
-        'k' => {
+        Action::MoveUp => {
             state.cursor = move_up(state)?;
             state.prev_op = None;
-            state
+            state.status = None;
         }
-        'h' => {
-            let cursor = move_out_of_dir(state)?;
+        Action::Top => {
+            state.cursor = 0;
+            state.prev_op = None;
+            state.status = None;
+        }
+        Action::Bottom => {
+            state.cursor = move_bottom(state)?;
+            state.prev_op = None;
+            state.status = None;
+        }
+        Action::ToggleHidden => {
+            state.show_hidden = !state.show_hidden;
+            state.cursor = 0;
+            state.prev_op = None;
+        }
+        Action::StartYank => state.pending_key = Some('y'),
+        Action::StartCut => state.pending_key = Some('d'),
+        Action::Paste => paste(state)?,
+        Action::Delete => delete_selected(state)?,
+        Action::Undo => {
+            let message = undo_last(state)?;
+            state.status = Some(message);
+        }
+        Action::StartBookmarkSet => state.bookmark_mode = Some('m'),
+        Action::StartBookmarkJump => state.bookmark_mode = Some('`'),
+        Action::StartSearch => {
+            state.search = Some(String::new());
+            state.search_input_active = true;
+            state.cursor = 0;
+        }
+        Action::MoveOut => {
             let op = Some(Op::new(OpKind::Out, state.dir.clone()));
+            let cursor = move_out_of_dir(state)?;
             state.cursor = cursor;
             state.prev_op = op;
-            state
+            state.dir = std::env::current_dir()?;
+            state.search = None;
+            state.reload()?;
+            state.rewatch()?;
+            state.status = None;
         }
-        'l' => {
+        Action::MoveIn => {
             state.cursor = move_into_dir(state)?;
             state.prev_op = None;
-            state
+            state.dir = std::env::current_dir()?;
+            state.search = None;
+            state.reload()?;
+            state.rewatch()?;
+            state.status = None;
         }
-        _ => state,
-    };
-    Ok(state)
+    }
+    Ok(false)
+}
+
+/// Width, in columns, of the " │ " separator painted between panes.
+const COLUMN_SEPARATOR_WIDTH: u16 = 3;
+
+/// Splits the terminal width into parent/current/preview column budgets,
+/// roughly matching the proportions ranger/yazi use.
+fn column_widths(total: u16) -> (u16, u16, u16) {
+    let usable = total.saturating_sub(COLUMN_SEPARATOR_WIDTH * 2).max(3);
+    let parent = ((usable as u32 * 20 / 100) as u16).max(1);
+    let preview = ((usable as u32 * 40 / 100) as u16).max(1);
+    let current = usable.saturating_sub(parent).saturating_sub(preview).max(1);
+    (parent, current, preview)
 }
 
-fn get_dir_content() -> Result<Vec<PathBuf>> {
-    let mut entries = Vec::new();
-    for entry in std::fs::read_dir(".")? {
-        let entry = entry?;
-        let path = entry.path();
-        entries.push(path);
+fn format_column(entries: &[Line], width: usize, height: usize) -> Vec<Line> {
+    let mut lines: Vec<Line> = entries
+        .iter()
+        .take(height)
+        .map(|entry| truncate_pad_line(entry, width))
+        .collect();
+    while lines.len() < height {
+        lines.push(vec![(None, " ".repeat(width))]);
     }
-    entries.sort();
-    Ok(entries)
+    lines
 }
 
-fn format_screen_lines(cursor: i32, content: Vec<PathBuf>) -> Result<Vec<String>> {
-    let content = match !content.is_empty() {
-        true => content,
-        false => vec![PathBuf::from("   ../")],
-    };
+fn parent_column_lines(parent_listing: &[PathBuf], current_dir_name: Option<&OsStr>) -> Vec<Line> {
+    parent_listing
+        .iter()
+        .map(|entry| {
+            let name = pathbuf_to_string(entry);
+            if entry.file_name() == current_dir_name {
+                plain_line(format!(" > {}", name.trim_start()))
+            } else {
+                plain_line(name)
+            }
+        })
+        .collect()
+}
 
+fn middle_column_lines(cursor: i32, content: &[PathBuf], scroll: usize, loading: bool) -> Vec<Line> {
     let mut lines = Vec::new();
-    let current_dir = std::env::current_dir()?;
-    lines.push(format!("{}", current_dir.display()));
-    lines.push(String::from(""));
+    if content.is_empty() && !loading {
+        lines.push(plain_line("../"));
+    } else {
+        for (i, entry) in content.iter().enumerate().skip(scroll) {
+            let name = pathbuf_to_string(entry);
+            if i as i32 == cursor {
+                lines.push(plain_line(format!(" > {}", name.trim_start())));
+            } else {
+                lines.push(plain_line(name));
+            }
+        }
+    }
+    if loading {
+        lines.push(plain_line("loading..."));
+    }
+    lines
+}
+
+/// Keeps `state.scroll` such that `state.cursor` stays within the
+/// `[scroll, scroll + body_height)` window, so the selection is never
+/// scrolled past the rendered rows and silently dropped by `format_column`'s
+/// `.take(body_height)`.
+fn clamp_scroll(state: &mut State, total: usize, body_height: usize) {
+    if body_height == 0 {
+        return;
+    }
+    let cursor = state.cursor.max(0) as usize;
+    if cursor < state.scroll {
+        state.scroll = cursor;
+    } else if cursor >= state.scroll + body_height {
+        state.scroll = cursor + 1 - body_height;
+    }
+    let max_scroll = total.saturating_sub(body_height);
+    state.scroll = state.scroll.min(max_scroll);
+}
+
+/// Renders the right-hand preview, reusing the last highlighted result when
+/// the selected path hasn't changed since instead of re-running `syntect`
+/// over the same file every frame.
+fn preview_column_lines(state: &mut State, selected: Option<&Path>) -> Vec<Line> {
+    if let (Preview::File(lines), Some(path)) = (&state.preview, selected) {
+        if !lines.is_empty() {
+            if let Some((cached_path, cached)) = &state.highlighted_preview {
+                if cached_path == path {
+                    return cached.clone();
+                }
+            }
+        }
+    }
+
+    let result = match &state.preview {
+        Preview::None => vec![plain_line("(no preview)")],
+        Preview::Binary => vec![plain_line("(binary file)")],
+        Preview::Dir(entries) if entries.is_empty() => vec![plain_line("(empty directory)")],
+        Preview::Dir(entries) => entries
+            .iter()
+            .map(|entry| plain_line(pathbuf_to_string(entry)))
+            .collect(),
+        Preview::File(lines) if lines.is_empty() => vec![plain_line("(empty file)")],
+        Preview::File(lines) => match selected {
+            Some(path) => highlight_preview(&state.syntax_set, &state.theme, path, lines),
+            None => lines.iter().map(|line| plain_line(line.clone())).collect(),
+        },
+    };
 
-    for entry in content {
-        lines.push(pathbuf_to_string(&entry));
+    if let Some(path) = selected {
+        state.highlighted_preview = Some((path.to_path_buf(), result.clone()));
     }
 
-    let index = (cursor + 2) as usize;
-    lines[index] = format!(" > {}", lines[index].trim_start());
+    result
+}
+
+/// Lays out the parent directory, the current listing (with the `>`
+/// cursor), and a preview of the highlighted entry side by side.
+fn format_screen_lines(state: &mut State) -> Result<Vec<Line>> {
+    let (width, height) = terminal::size()?;
+    let (parent_w, current_w, preview_w) = column_widths(width);
+    let body_height = height.saturating_sub(2).max(1) as usize;
+
+    clamp_scroll(state, state.visible.len(), body_height);
+
+    let parent_col = format_column(
+        &parent_column_lines(&state.parent_listing, state.dir.file_name()),
+        parent_w as usize,
+        body_height,
+    );
+    let current_col = format_column(
+        &middle_column_lines(
+            state.cursor,
+            &state.visible,
+            state.scroll,
+            state.loader.is_loading(),
+        ),
+        current_w as usize,
+        body_height,
+    );
+    let selected = selected_path(state);
+    let preview_col = format_column(
+        &preview_column_lines(state, selected.as_deref()),
+        preview_w as usize,
+        body_height,
+    );
+
+    let mut lines = Vec::with_capacity(body_height + 2);
+    lines.push(plain_line(format!("{}", state.dir.display())));
+    lines.push(plain_line(String::new()));
+    for i in 0..body_height {
+        lines.push(combine_row(&parent_col[i], &current_col[i], &preview_col[i]));
+    }
 
     Ok(lines)
 }
@@ -210,20 +1178,106 @@ fn pathbuf_to_string(path: &Path) -> String {
     }
 }
 
-fn read_char() -> Result<char> {
-    loop {
-        if let Ok(Event::Key(KeyEvent {
-            code: KeyCode::Char(c),
-            ..
-        })) = event::read()
-        {
-            return Ok(c);
+/// Waits up to `timeout` for a keypress, returning `None` if none arrives so
+/// the caller can keep driving the directory loader and filesystem watcher
+/// instead of blocking indefinitely.
+fn poll_key(timeout: Duration) -> Result<Option<KeyEvent>> {
+    if event::poll(timeout)? {
+        if let Event::Key(event) = event::read()? {
+            return Ok(Some(event));
         }
     }
+    Ok(None)
+}
+
+/// A keypress as seen by the `/` search prompt, which needs Backspace/Enter/
+/// Esc in addition to plain characters.
+enum SearchKey {
+    Char(char),
+    Backspace,
+    Enter,
+    Esc,
+}
+
+fn classify_search_key(event: KeyEvent) -> Option<SearchKey> {
+    match event.code {
+        KeyCode::Char(c) => Some(SearchKey::Char(c)),
+        KeyCode::Backspace => Some(SearchKey::Backspace),
+        KeyCode::Enter => Some(SearchKey::Enter),
+        KeyCode::Esc => Some(SearchKey::Esc),
+        _ => None,
+    }
+}
+
+/// Handles a keypress while the `/` search prompt is collecting a query.
+fn handle_search_key(key: SearchKey, state: &mut State) {
+    match key {
+        SearchKey::Char(c) => {
+            if let Some(query) = &mut state.search {
+                query.push(c);
+            }
+            state.cursor = 0;
+        }
+        SearchKey::Backspace => {
+            if let Some(query) = &mut state.search {
+                query.pop();
+            }
+            state.cursor = 0;
+        }
+        SearchKey::Enter => state.search_input_active = false,
+        SearchKey::Esc => {
+            state.search = None;
+            state.search_input_active = false;
+        }
+    }
+}
+
+/// Whether `name` contains every character of `query`, in order, ignoring
+/// case (a simple subsequence/fuzzy match).
+fn subsequence_match(query: &str, name: &str) -> bool {
+    let mut name_chars = name.to_lowercase().chars().collect::<Vec<_>>().into_iter();
+    query
+        .to_lowercase()
+        .chars()
+        .all(|qc| name_chars.any(|nc| nc == qc))
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(OsStr::to_str)
+        .map(|name| name.starts_with('.'))
+        .unwrap_or(false)
+}
+
+/// The entries `format_screen_lines`, `move_into_dir` and friends should
+/// actually show and index into: the loaded listing with dotfiles hidden
+/// unless `show_hidden` is set, further narrowed to the active search query.
+///
+/// This is only ever recomputed by `State::refresh_visible`, once per frame
+/// in `run`; read sites within a frame should use `state.visible` instead of
+/// calling this directly, since it's an O(n) pass over the loaded listing.
+fn compute_visible_entries(state: &State) -> Vec<PathBuf> {
+    let shown: Vec<&PathBuf> = state
+        .loaded
+        .iter()
+        .filter(|path| state.show_hidden || !is_hidden(path))
+        .collect();
+    match &state.search {
+        Some(query) if !query.is_empty() => shown
+            .into_iter()
+            .filter(|path| {
+                let name = path.file_name().and_then(OsStr::to_str).unwrap_or("");
+                subsequence_match(query, name)
+            })
+            .cloned()
+            .collect(),
+        _ => shown.into_iter().cloned().collect(),
+    }
 }
 
 fn move_down(state: &State) -> Result<i32> {
-    let cursor = if state.cursor + 1 < (state.screen_lines.len() - 2) as i32 {
+    let max = (state.visible.len() as i32 - 1).max(0);
+    let cursor = if state.cursor < max {
         state.cursor + 1
     } else {
         state.cursor
@@ -240,20 +1294,20 @@ fn move_up(state: &State) -> Result<i32> {
     Ok(cursor)
 }
 
+fn move_bottom(state: &State) -> Result<i32> {
+    Ok((state.visible.len() as i32 - 1).max(0))
+}
+
 fn move_out_of_dir(state: &State) -> Result<i32> {
     std::env::set_current_dir("..")?;
     Ok(state.cursor)
 }
 
 fn move_into_dir(state: &State) -> Result<i32> {
-    let path = state.screen_lines[(state.cursor + 2) as usize].trim_start();
-    let newdir = path.trim_end_matches('/');
-    let newdir = str::replace(newdir, ">", " ");
-    let newdir = newdir.trim_start();
-    let current_dir = std::env::current_dir()?;
-    let newdir = current_dir.join(newdir);
-    if path.ends_with('/') {
-        std::env::set_current_dir(newdir)?;
+    if let Some(path) = state.visible.get(state.cursor as usize) {
+        if path.is_dir() {
+            std::env::set_current_dir(path)?;
+        }
     }
     Ok(state.cursor)
 }